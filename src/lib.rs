@@ -5,19 +5,75 @@ use std::{
     time::Duration,
 };
 
+use futures::Stream;
 use tokio::time::{Instant, Sleep};
 
+mod heap;
+pub use heap::MuxTimerHeap;
+
+/// Maximum number of ordinals [MuxTimer::poll_expired] drains in a single call, so that a thundering
+/// herd of simultaneous deadlines cannot starve the executor of other work.
+pub const YIELD_COUNT: usize = 32;
+
+/// A bounded set of ordinals that expired together, yielded by [MuxTimer::poll_expired].
+#[derive(Debug, Clone, Copy)]
+pub struct ExpiredSet<const N: usize> {
+    ordinals: [usize; N],
+    len: usize,
+}
+
+impl<const N: usize> ExpiredSet<N> {
+    fn new() -> Self {
+        Self {
+            ordinals: [0; N],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, ordinal: usize) {
+        self.ordinals[self.len] = ordinal;
+        self.len += 1;
+    }
+
+    /// Returns the number of ordinals in the set.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns an iterator over the expired ordinals, in the order they were drained.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.ordinals[..self.len].iter().copied()
+    }
+}
+
+impl<const N: usize> IntoIterator for ExpiredSet<N> {
+    type Item = usize;
+    type IntoIter = std::iter::Take<std::array::IntoIter<usize, N>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ordinals.into_iter().take(self.len)
+    }
+}
+
 /// Timer for a limited set of events that are represented by their ordinals.
 /// It multiplexes over a single tokio [Sleep] instance.
 /// Deadlines for the same event are coalesced to the sooner one if it has not yet fired.
 ///
 /// Deadlines are stored on a stack-allocated array of size `N`, and the ordinals are used to index into it,
-/// so the maximum supported ordinal will be `N - 1`. The implementation is designed for small `N` (think single digits).
+/// so the maximum supported ordinal will be `N - 1`. The implementation is designed for small `N` (think single digits),
+/// since cancelling the armed event and re-arming after a fire scan the `deadlines` array in `O(N)`. For hundreds or thousands of events, see
+/// [MuxTimerHeap] instead, which keeps the same arm/cancel surface but tracks the soonest deadline in `O(log N)`.
 ///
 /// Mapping between ordinals and events is up to the user.
 #[derive(Debug)]
 pub struct MuxTimer<const N: usize> {
     deadlines: [Option<Instant>; N],
+    periods: [Option<Duration>; N],
     sleep: Pin<Box<Sleep>>,
     armed_ordinal: usize,
 }
@@ -26,6 +82,7 @@ impl<const N: usize> Default for MuxTimer<N> {
     fn default() -> Self {
         Self {
             deadlines: [None; N],
+            periods: [None; N],
             sleep: Box::pin(tokio::time::sleep(Duration::ZERO)),
             armed_ordinal: N,
         }
@@ -39,6 +96,17 @@ impl<const N: usize> MuxTimer<N> {
         self.fire_at(ordinal, Instant::now() + timeout)
     }
 
+    /// Arm timer for event with `ordinal` to fire every `period`, starting after the first `period`
+    /// elapses. Once fired, the event is automatically rescheduled `period` after its fired deadline
+    /// (rather than after `Instant::now()`) so recurring events don't drift, until [MuxTimer::cancel]
+    /// is called for the same `ordinal`.
+    /// Returns `true` if the timer was armed, `false` if it was already armed for the same event with sooner deadline.
+    pub fn fire_every(&mut self, ordinal: impl Into<usize>, period: Duration) -> bool {
+        let ordinal = ordinal.into();
+        self.periods[ordinal] = Some(period);
+        self.fire_after(ordinal, period)
+    }
+
     /// Fire timer for event with `ordinal` at `deadline`.
     /// Returns `true` if the timer was armed, `false` if it was already armed for the same event with sooner deadline.
     pub fn fire_at(&mut self, ordinal: impl Into<usize>, deadline: Instant) -> bool {
@@ -77,6 +145,24 @@ impl<const N: usize> MuxTimer<N> {
         &self.deadlines
     }
 
+    /// Cancel the timer for event with `ordinal`.
+    /// Returns `true` if a deadline was cleared, `false` if none was set.
+    pub fn cancel(&mut self, ordinal: impl Into<usize>) -> bool {
+        let ordinal = ordinal.into();
+        self.periods[ordinal] = None;
+        if self.deadlines[ordinal].take().is_none() {
+            return false;
+        }
+        if self.armed_ordinal == ordinal {
+            if let Some((ordinal, deadline)) = self.soonest_event() {
+                self.arm(ordinal, deadline);
+            } else {
+                self.armed_ordinal = N;
+            }
+        }
+        true
+    }
+
     fn soonest_event(&self) -> Option<(usize, Instant)> {
         self.deadlines
             .iter()
@@ -84,33 +170,164 @@ impl<const N: usize> MuxTimer<N> {
             .filter_map(|(ordinal, slot)| slot.map(|deadline| (ordinal, deadline)))
             .min_by(|(_, x), (_, y)| x.cmp(y))
     }
-}
 
-/// Wait for the next event and return its ordinal.
-/// Panics if the timer is not armed.
-impl<const N: usize> Future for MuxTimer<N> {
-    type Output = usize;
-
-    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        assert!(self.armed_ordinal < N);
+    /// Polls the armed sleep, and on firing, reschedules a recurring ordinal off of its fired
+    /// deadline and re-arms on the next-soonest remaining deadline.
+    fn poll_fire(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<usize> {
         ready!(self.sleep.as_mut().poll(cx));
         let fired_ordinal = std::mem::replace(&mut self.armed_ordinal, N);
         let fired_deadline = self.deadlines[fired_ordinal].take().expect("armed");
         assert_eq!(fired_deadline, self.sleep.deadline());
+        if let Some(period) = self.periods[fired_ordinal] {
+            self.deadlines[fired_ordinal] = Some(fired_deadline + period);
+        }
         if let Some((ordinal, deadline)) = self.soonest_event() {
             self.arm(ordinal, deadline);
         }
         Poll::Ready(fired_ordinal)
     }
+
+    /// Wait for the armed sleep to fire, then drain every ordinal whose deadline has already
+    /// elapsed (not just the one that woke the sleep), re-arming on the soonest deadline still
+    /// in the future. Recurring ordinals (see [MuxTimer::fire_every]) are rescheduled off their
+    /// fired deadline as they are drained.
+    ///
+    /// Panics if the timer is not armed.
+    ///
+    /// At most [YIELD_COUNT] ordinals are drained per call; if more were already expired, the
+    /// returned set is truncated to that many and the task is woken again immediately so the
+    /// rest are drained on a subsequent call, rather than blocking the executor on one herd.
+    pub fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<ExpiredSet<N>> {
+        assert!(self.armed_ordinal < N);
+        ready!(self.sleep.as_mut().poll(cx));
+
+        let now = Instant::now();
+        let mut expired = ExpiredSet::new();
+        let mut truncated = false;
+        for ordinal in 0..N {
+            let Some(deadline) = self.deadlines[ordinal] else {
+                continue;
+            };
+            if deadline > now {
+                continue;
+            }
+            if expired.len() >= YIELD_COUNT {
+                truncated = true;
+                break;
+            }
+            self.deadlines[ordinal] = self.periods[ordinal].map(|period| deadline + period);
+            expired.push(ordinal);
+        }
+
+        match self.soonest_event() {
+            Some((ordinal, deadline)) => self.arm(ordinal, deadline),
+            None => self.armed_ordinal = N,
+        }
+        if truncated {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Ready(expired)
+    }
+}
+
+/// Wait for the next event and return its ordinal.
+/// Panics if the timer is not armed.
+impl<const N: usize> Future for MuxTimer<N> {
+    type Output = usize;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        assert!(self.armed_ordinal < N);
+        self.poll_fire(cx)
+    }
+}
+
+/// Yields the ordinal of each event as it fires. Recurring events (armed via [MuxTimer::fire_every])
+/// are rescheduled and keep appearing on the stream; one-shot events are cleared after firing, as
+/// with the `Future` impl.
+///
+/// The stream ends once the timer is left disarmed, i.e. there are no pending one-shot deadlines
+/// and no recurring ordinals.
+impl<const N: usize> Stream for MuxTimer<N> {
+    type Item = usize;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if !self.is_armed() {
+            return Poll::Ready(None);
+        }
+        self.poll_fire(cx).map(Some)
+    }
+}
+
+/// Error returned by [WithTimeout] when the bound ordinal's deadline fires before the wrapped
+/// future completes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed(());
+
+impl std::fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+impl<const N: usize> MuxTimer<N> {
+    /// Race `fut` against the event with `ordinal` firing after `timeout`. Resolves to `Ok` with
+    /// `fut`'s output if it completes first, cancelling the ordinal, or to `Err(Elapsed)` if the
+    /// ordinal's deadline fires first.
+    ///
+    /// This lets callers bound many sequential operations off of one shared [MuxTimer] rather than
+    /// allocating a fresh [tokio::time::timeout] per call.
+    pub fn with_timeout<F: Future>(
+        &mut self,
+        ordinal: impl Into<usize>,
+        timeout: Duration,
+        fut: F,
+    ) -> WithTimeout<'_, N, F> {
+        let ordinal = ordinal.into();
+        self.fire_after(ordinal, timeout);
+        WithTimeout {
+            timer: self,
+            ordinal,
+            fut: Box::pin(fut),
+        }
+    }
+}
+
+/// Future returned by [MuxTimer::with_timeout].
+pub struct WithTimeout<'a, const N: usize, F> {
+    timer: &'a mut MuxTimer<N>,
+    ordinal: usize,
+    fut: Pin<Box<F>>,
+}
+
+impl<'a, const N: usize, F: Future> Future for WithTimeout<'a, N, F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Poll::Ready(output) = this.fut.as_mut().poll(cx) {
+            this.timer.cancel(this.ordinal);
+            return Poll::Ready(Ok(output));
+        }
+        loop {
+            match Pin::new(&mut *this.timer).poll_fire(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(fired) if fired == this.ordinal => return Poll::Ready(Err(Elapsed(()))),
+                Poll::Ready(_) => continue,
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
 
+    use futures::StreamExt;
     use tokio::pin;
 
-    use super::MuxTimer;
+    use super::{Elapsed, MuxTimer};
 
     const EVENT_A: usize = 0;
     const EVENT_B: usize = 1;
@@ -155,4 +372,107 @@ mod tests {
         assert_eq!(event, EVENT_A);
         assert_eq!(timer.deadline(), None);
     }
+
+    #[tokio::main(flavor = "current_thread", start_paused = true)]
+    #[test]
+    async fn cancellation() {
+        let mut timer: MuxTimer<3> = MuxTimer::default();
+
+        assert!(timer.fire_after(EVENT_A, Duration::from_millis(50)));
+        assert!(timer.fire_after(EVENT_B, Duration::from_millis(100)));
+
+        // Cancelling the armed ordinal re-arms on the next-soonest deadline.
+        assert!(timer.cancel(EVENT_A));
+        assert!(!timer.cancel(EVENT_A));
+
+        pin!(timer);
+
+        let event = timer.as_mut().await;
+        assert_eq!(event, EVENT_B);
+        assert_eq!(timer.deadline(), None);
+    }
+
+    #[tokio::main(flavor = "current_thread", start_paused = true)]
+    #[test]
+    async fn cancel_disarms_when_nothing_left() {
+        let mut timer: MuxTimer<3> = MuxTimer::default();
+
+        assert!(timer.fire_after(EVENT_A, Duration::from_millis(50)));
+        assert!(timer.cancel(EVENT_A));
+
+        assert!(!timer.is_armed());
+        assert_eq!(timer.deadline(), None);
+    }
+
+    #[tokio::main(flavor = "current_thread", start_paused = true)]
+    #[test]
+    async fn recurring_stream() {
+        let mut timer: MuxTimer<3> = MuxTimer::default();
+
+        assert!(timer.fire_every(EVENT_A, Duration::from_millis(50)));
+        assert!(timer.fire_after(EVENT_B, Duration::from_millis(120)));
+
+        pin!(timer);
+
+        // EVENT_A fires every 50ms and is rescheduled each time, so it fires twice before the
+        // one-shot EVENT_B does.
+        assert_eq!(timer.next().await, Some(EVENT_A));
+        assert_eq!(timer.next().await, Some(EVENT_A));
+        assert_eq!(timer.next().await, Some(EVENT_B));
+
+        // EVENT_A is still recurring, so the stream doesn't end.
+        assert_eq!(timer.next().await, Some(EVENT_A));
+
+        assert!(timer.cancel(EVENT_A));
+        assert_eq!(timer.next().await, None);
+    }
+
+    #[tokio::main(flavor = "current_thread", start_paused = true)]
+    #[test]
+    async fn expired_batch() {
+        let mut timer: MuxTimer<3> = MuxTimer::default();
+
+        assert!(timer.fire_after(EVENT_A, Duration::from_millis(50)));
+        assert!(timer.fire_after(EVENT_B, Duration::from_millis(50)));
+        assert!(timer.fire_after(EVENT_C, Duration::from_millis(150)));
+
+        let expired = std::future::poll_fn(|cx| timer.poll_expired(cx)).await;
+        let mut ordinals: Vec<_> = expired.iter().collect();
+        ordinals.sort();
+        assert_eq!(ordinals, vec![EVENT_A, EVENT_B]);
+
+        assert_eq!(timer.deadlines()[EVENT_C], timer.deadline());
+    }
+
+    #[tokio::main(flavor = "current_thread", start_paused = true)]
+    #[test]
+    async fn with_timeout_fut_wins() {
+        let mut timer: MuxTimer<3> = MuxTimer::default();
+
+        let result = timer
+            .with_timeout(EVENT_A, Duration::from_millis(100), async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                "done"
+            })
+            .await;
+
+        assert_eq!(result, Ok("done"));
+        // The ordinal is cancelled once the wrapped future wins.
+        assert_eq!(timer.deadline(), None);
+    }
+
+    #[tokio::main(flavor = "current_thread", start_paused = true)]
+    #[test]
+    async fn with_timeout_elapses() {
+        let mut timer: MuxTimer<3> = MuxTimer::default();
+
+        let result = timer
+            .with_timeout(EVENT_A, Duration::from_millis(10), async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                "done"
+            })
+            .await;
+
+        assert_eq!(result, Err(Elapsed(())));
+    }
 }